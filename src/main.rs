@@ -1,20 +1,68 @@
 use anyhow::{Context, Result};
-use hyper::header::CONTENT_TYPE;
+use hyper::header::{CONTENT_TYPE, HOST};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use multer::Multipart;
 use nanoid::nanoid;
 use rusqlite::{params, Connection, OpenFlags, NO_PARAMS};
-use url::form_urlencoded;
+use url::{form_urlencoded, Url};
 
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-fn respond_with_shortlink<S: AsRef<str>>(shortlink: S) -> Response<Body> {
+type Db = Arc<Mutex<Connection>>;
+
+/// Runtime configuration, populated from the environment so a single binary
+/// can be deployed in different environments without recompilation.
+struct Config {
+    host: String,
+    port: u16,
+    db_path: String,
+    id_len: usize,
+    base_url: Option<String>,
+}
+
+impl Config {
+    fn from_env() -> Self {
+        use std::env::var;
+        Config {
+            host: var("HEDGE_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
+            port: var("HEDGE_PORT")
+                .ok()
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(3000),
+            db_path: var("HEDGE_DB").unwrap_or_else(|_| "./urls.db_3".to_string()),
+            id_len: var("HEDGE_ID_LEN")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(4),
+            base_url: var("HEDGE_BASE_URL").ok().filter(|s| !s.is_empty()),
+        }
+    }
+
+    fn listen_addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+fn respond_with_shortlink<S: AsRef<str>>(
+    base: Option<&str>,
+    scheme: &str,
+    host: &[u8],
+    id: S,
+) -> Response<Body> {
+    let shortlink = match base {
+        Some(base) => format!("{}/{}", base.trim_end_matches('/'), id.as_ref()),
+        None => {
+            let host = String::from_utf8_lossy(host);
+            format!("{}://{}/{}", scheme, host, id.as_ref())
+        }
+    };
     Response::builder()
         .status(StatusCode::OK)
         .header("content-type", "text/html")
-        .body(Body::from(shortlink.as_ref().to_string()))
+        .body(Body::from(shortlink))
         .unwrap()
 }
 
@@ -22,56 +70,324 @@ fn respond_with_status(s: StatusCode) -> Response<Body> {
     Response::builder().status(s).body(Body::empty()).unwrap()
 }
 
-fn shorten<S: AsRef<str>>(url: S, conn: &mut Connection) -> Result<String> {
-    let mut stmt = conn.prepare("select * from urls where link = ?1")?;
-    let mut rows = stmt.query(params![url.as_ref().to_string()])?;
+/// Destination hosts the shortener refuses to mint redirects for. Populated at
+/// compile time so a deployment can't be turned into an open redirector to
+/// known-malicious hosts.
+const BLOCKED_DOMAINS: &[&str] = &[];
+
+fn is_blocked(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    BLOCKED_DOMAINS
+        .iter()
+        .any(|b| host == *b || host.ends_with(&format!(".{}", b)))
+}
+
+/// A submitted destination is acceptable only if it is an absolute `http`/
+/// `https` URL with a host that isn't on the blocklist.
+fn is_valid_destination(url: &str) -> bool {
+    match Url::parse(url) {
+        Ok(u) => match (u.scheme(), u.host_str()) {
+            ("http", Some(h)) | ("https", Some(h)) => !is_blocked(h),
+            _ => false,
+        },
+        Err(_) => false,
+    }
+}
+
+/// Outcome of a shorten request, mapped to an HTTP status by the caller.
+enum Shortened {
+    Ok(String),
+    Taken,
+    Invalid,
+}
+
+/// Outcome of resolving a short code to its destination.
+enum Resolved {
+    Found(String),
+    Gone,
+    Missing,
+}
+
+/// Current wall-clock time as seconds since the Unix epoch.
+fn now_secs() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Turn a submitted `expires_in` value into an absolute expiry timestamp.
+///
+/// Two forms are accepted: a TTL in whole seconds (`"3600"`), or an absolute
+/// RFC 3339 / ISO 8601 instant (`"2026-07-25T12:00:00Z"`). Returns the epoch
+/// seconds the row should expire at, or `None` when the input can't be parsed.
+fn parse_expires(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<i64>() {
+        return Some(now_secs() + secs);
+    }
+    parse_rfc3339(value)
+}
+
+/// Resolve an optional `expires_in` field into a concrete expiry.
+///
+/// `None`/empty means "no expiry" (`Ok(None)`); a present but unparsable value
+/// is rejected (`Err(())`) so a malformed TTL can't silently mint a permanent
+/// link.
+fn resolve_expiry(value: Option<&str>) -> Result<Option<i64>, ()> {
+    match value {
+        Some(v) if !v.trim().is_empty() => parse_expires(v).map(Some).ok_or(()),
+        _ => Ok(None),
+    }
+}
+
+/// Minimal RFC 3339 parser for the `YYYY-MM-DDTHH:MM:SS(Z|±HH:MM)` shape,
+/// returning the instant as epoch seconds. The offset may also be written
+/// `±HHMM` or `±HH`, or left out for UTC. A date or time field that is not a
+/// number, or a month or day out of range, yields `None`. The `:` separators
+/// are not checked, text after the seconds field is skipped up to the first
+/// `+` or `-` (so fractional seconds and other trailing text are ignored), and
+/// only the first four digits of a `±HHMM` offset are read.
+fn parse_rfc3339(s: &str) -> Option<i64> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 19 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    if bytes[10] != b'T' && bytes[10] != b't' && bytes[10] != b' ' {
+        return None;
+    }
+    let year: i64 = s.get(0..4)?.parse().ok()?;
+    let month: i64 = s.get(5..7)?.parse().ok()?;
+    let day: i64 = s.get(8..10)?.parse().ok()?;
+    let hour: i64 = s.get(11..13)?.parse().ok()?;
+    let min: i64 = s.get(14..16)?.parse().ok()?;
+    let sec: i64 = s.get(17..19)?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Parse an optional trailing timezone offset; default to UTC.
+    let mut offset = 0i64;
+    let tz = &s[19..];
+    let tz = tz.strip_suffix(|c| c == 'Z' || c == 'z').unwrap_or(tz);
+    let tz = match tz.find(|c| c == '+' || c == '-') {
+        Some(idx) => &tz[idx..],
+        None => "",
+    };
+    if !tz.is_empty() {
+        let sign = if tz.as_bytes()[0] == b'-' { -1 } else { 1 };
+        let rest = &tz[1..];
+        let (oh, om) = match rest.split_once(':') {
+            Some((h, m)) => (h, m),
+            None => (rest.get(0..2)?, rest.get(2..4).unwrap_or("00")),
+        };
+        offset = sign * (oh.parse::<i64>().ok()? * 3600 + om.parse::<i64>().ok()? * 60);
+    }
+
+    // Days since the Unix epoch using the days-from-civil algorithm.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + hour * 3600 + min * 60 + sec - offset)
+}
+
+fn is_unique_violation(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.code == rusqlite::ErrorCode::ConstraintViolation
+    )
+}
+
+/// Shorten `url`, optionally under a caller supplied `alias`.
+///
+/// Returns `Shortened::Ok(code)` with the short code to hand back,
+/// `Shortened::Taken` when a requested alias is already claimed by a different
+/// link, or `Shortened::Invalid` when the destination isn't a safe URL.
+fn shorten<S: AsRef<str>>(
+    url: S,
+    alias: Option<&str>,
+    expires_at: Option<i64>,
+    id_len: usize,
+    conn: &mut Connection,
+) -> Result<Shortened> {
+    let url = url.as_ref();
+
+    if !is_valid_destination(url) {
+        return Ok(Shortened::Invalid);
+    }
+
+    // Resubmitting the same link returns the code it already has.
+    let mut stmt = conn.prepare("select shortlink from urls where link = ?1")?;
+    let mut rows = stmt.query(params![url.to_string()])?;
     if let Some(row) = rows.next()? {
-        return Ok(row.get(1)?);
-    } else {
-        let new_id = nanoid!(4);
-        conn.execute(
-            "insert into urls (link, shortlink) values (?1, ?2)",
-            params![url.as_ref().to_string(), new_id],
-        )?;
-        return Ok(new_id);
+        return Ok(Shortened::Ok(row.get(0)?));
+    }
+    drop(rows);
+    drop(stmt);
+
+    let insert = "insert into urls (link, shortlink, expires_at) values (?1, ?2, ?3)";
+    if let Some(alias) = alias {
+        return match conn.execute(insert, params![url.to_string(), alias, expires_at]) {
+            Ok(_) => Ok(Shortened::Ok(alias.to_string())),
+            Err(ref e) if is_unique_violation(e) => Ok(Shortened::Taken),
+            Err(e) => Err(e.into()),
+        };
+    }
+
+    // Auto-generated codes retry on collision and widen the keyspace after a
+    // few failures so a saturated alphabet can't spin forever.
+    let mut n = id_len;
+    let mut failures = 0;
+    loop {
+        let new_id = nanoid!(n);
+        match conn.execute(insert, params![url.to_string(), new_id, expires_at]) {
+            Ok(_) => return Ok(Shortened::Ok(new_id)),
+            Err(ref e) if is_unique_violation(e) => {
+                failures += 1;
+                if failures % 3 == 0 {
+                    n += 1;
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
     }
 }
 
-fn get_link<S: AsRef<str>>(url: S, conn: &mut Connection) -> Result<Option<String>> {
+fn get_link<S: AsRef<str>>(url: S, conn: &mut Connection) -> Result<Resolved> {
     let url = url.as_ref();
-    let mut stmt = conn.prepare("select * from urls where shortlink = ?1")?;
+    let mut stmt = conn.prepare("select link, expires_at from urls where shortlink = ?1")?;
     let mut rows = stmt.query(params![url.to_string()])?;
     if let Some(row) = rows.next()? {
-        return Ok(row.get(0)?);
+        let link: String = row.get(0)?;
+        let expires_at: Option<i64> = row.get(1)?;
+        drop(rows);
+        drop(stmt);
+        if let Some(exp) = expires_at {
+            if exp <= now_secs() {
+                return Ok(Resolved::Gone);
+            }
+        }
+        conn.execute(
+            "update urls set clicks = clicks + 1 where shortlink = ?1",
+            params![url.to_string()],
+        )?;
+        return Ok(Resolved::Found(link));
+    } else {
+        return Ok(Resolved::Missing);
+    }
+}
+
+/// Fetch the `(link, shortlink, clicks)` tuple backing a short code, if any.
+fn get_stats<S: AsRef<str>>(
+    id: S,
+    conn: &mut Connection,
+) -> Result<Option<(String, String, i64)>> {
+    let id = id.as_ref();
+    let mut stmt = conn.prepare("select link, shortlink, clicks from urls where shortlink = ?1")?;
+    let mut rows = stmt.query(params![id.to_string()])?;
+    if let Some(row) = rows.next()? {
+        return Ok(Some((row.get(0)?, row.get(1)?, row.get(2)?)));
     } else {
         return Ok(None);
     }
 }
 
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 async fn process_multipart(
     body: Body,
     boundary: String,
-    conn: &mut Connection,
+    scheme: &str,
+    host: &[u8],
+    config: &Config,
+    db: &Db,
 ) -> Result<Response<Body>> {
     let mut m = Multipart::new(body, boundary);
-    if let Some(field) = m.next_field().await? {
-        if field.name() == Some("shorten") {
-            let content = field
-                .text()
-                .await
-                .with_context(|| format!("Expected field name"))?;
-
-            let shortlink = shorten(content, conn)?;
-            return Ok(respond_with_shortlink(shortlink));
+    let mut content = None;
+    let mut alias = None;
+    let mut expires_in = None;
+    while let Some(field) = m.next_field().await? {
+        match field.name() {
+            Some("shorten") => {
+                content = Some(
+                    field
+                        .text()
+                        .await
+                        .with_context(|| format!("Expected field name"))?,
+                );
+            }
+            Some("alias") => {
+                alias = Some(field.text().await?);
+            }
+            Some("expires_in") => {
+                expires_in = Some(field.text().await?);
+            }
+            _ => {}
         }
     }
+    if let Some(content) = content {
+        let alias = alias.filter(|a| !a.is_empty());
+        let expires_at = match resolve_expiry(expires_in.as_deref()) {
+            Ok(e) => e,
+            Err(()) => return Ok(respond_with_status(StatusCode::UNPROCESSABLE_ENTITY)),
+        };
+        let outcome = {
+            let mut conn = db.lock().unwrap();
+            shorten(content, alias.as_deref(), expires_at, config.id_len, &mut *conn)?
+        };
+        return match outcome {
+            Shortened::Ok(shortlink) => Ok(respond_with_shortlink(
+                config.base_url.as_deref(),
+                scheme,
+                host,
+                shortlink,
+            )),
+            Shortened::Taken => Ok(respond_with_status(StatusCode::CONFLICT)),
+            Shortened::Invalid => Ok(respond_with_status(StatusCode::UNPROCESSABLE_ENTITY)),
+        };
+    }
     Ok(Response::builder()
         .status(StatusCode::OK)
         .body(Body::empty())?)
 }
 
-async fn shortner_service(req: Request<Body>) -> Result<Response<Body>> {
-    let mut conn = init_db("./urls.db_3").unwrap();
+async fn shortner_service(
+    req: Request<Body>,
+    db: Db,
+    config: Arc<Config>,
+) -> Result<Response<Body>> {
+    let scheme = req
+        .headers()
+        .get("X-Forwarded-Proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("http")
+        .to_string();
+    let host = req
+        .headers()
+        .get(HOST)
+        .map(|v| v.as_bytes().to_vec())
+        .unwrap_or_else(|| config.listen_addr().into_bytes());
 
     match req.method() {
         &Method::POST => {
@@ -91,31 +407,79 @@ async fn shortner_service(req: Request<Body>) -> Result<Response<Body>> {
                     .collect::<HashMap<String, String>>();
 
                 if let Some(n) = params.get("shorten") {
-                    let s = shorten(n, &mut conn)?;
-                    return Ok(respond_with_shortlink(s));
+                    let alias = params.get("alias").filter(|a| !a.is_empty());
+                    let expires_at = match resolve_expiry(params.get("expires_in").map(|s| s.as_str())) {
+                        Ok(e) => e,
+                        Err(()) => return Ok(respond_with_status(StatusCode::UNPROCESSABLE_ENTITY)),
+                    };
+                    let outcome = {
+                        let mut conn = db.lock().unwrap();
+                        shorten(n, alias.map(|s| s.as_str()), expires_at, config.id_len, &mut *conn)?
+                    };
+                    return match outcome {
+                        Shortened::Ok(s) => Ok(respond_with_shortlink(
+                            config.base_url.as_deref(),
+                            &scheme,
+                            &host,
+                            s,
+                        )),
+                        Shortened::Taken => Ok(respond_with_status(StatusCode::CONFLICT)),
+                        Shortened::Invalid => {
+                            Ok(respond_with_status(StatusCode::UNPROCESSABLE_ENTITY))
+                        }
+                    };
                 } else {
                     return Ok(respond_with_status(StatusCode::UNPROCESSABLE_ENTITY));
                 }
             }
 
-            return process_multipart(req.into_body(), boundary.unwrap(), &mut conn).await;
+            return process_multipart(
+                req.into_body(),
+                boundary.unwrap(),
+                &scheme,
+                &host,
+                &config,
+                &db,
+            )
+            .await;
         }
         &Method::GET => {
-            let shortlink = req.uri().path().to_string();
-            let link = get_link(&shortlink[1..], &mut conn);
-            if let Some(l) = link.unwrap() {
-                Ok(Response::builder()
+            let path = req.uri().path().to_string();
+            if let Some(id) = path.strip_prefix("/api/stats/") {
+                let stats = {
+                    let mut conn = db.lock().unwrap();
+                    get_stats(id, &mut *conn)?
+                };
+                return match stats {
+                    Some((link, shortlink, clicks)) => Ok(Response::builder()
+                        .status(StatusCode::OK)
+                        .header("content-type", "application/json")
+                        .body(Body::from(format!(
+                            "{{\"link\":\"{}\",\"shortlink\":\"{}\",\"clicks\":{}}}",
+                            json_escape(&link),
+                            json_escape(&shortlink),
+                            clicks
+                        )))?),
+                    None => Ok(respond_with_status(StatusCode::NOT_FOUND)),
+                };
+            }
+
+            let shortlink = path;
+            let resolved = {
+                let mut conn = db.lock().unwrap();
+                get_link(&shortlink[1..], &mut *conn)?
+            };
+            match resolved {
+                Resolved::Found(l) => Ok(Response::builder()
                     .header("Location", &l)
                     .header("content-type", "text/html")
                     .status(StatusCode::MOVED_PERMANENTLY)
                     .body(Body::from(format!(
                         "You will be redirected to: {}. If not, click the link.",
                         &l
-                    )))?)
-            } else {
-                Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(Body::empty())?)
+                    )))?),
+                Resolved::Gone => Ok(respond_with_status(StatusCode::GONE)),
+                Resolved::Missing => Ok(respond_with_status(StatusCode::NOT_FOUND)),
             }
         }
         _ => {
@@ -131,10 +495,14 @@ fn init_db<P: AsRef<Path>>(p: P) -> Result<Connection> {
         p,
         OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE,
     )?;
+    conn.pragma_update(None, "journal_mode", &"WAL")?;
+    conn.pragma_update(None, "busy_timeout", &5000)?;
     conn.execute(
         "CREATE TABLE IF NOT EXISTS urls (
             link TEXT PRIMARY KEY,
-            shortlink TEXT NOT NULL
+            shortlink TEXT NOT NULL UNIQUE,
+            clicks INTEGER DEFAULT 0,
+            expires_at INTEGER
         )",
         NO_PARAMS,
     )?;
@@ -143,9 +511,38 @@ fn init_db<P: AsRef<Path>>(p: P) -> Result<Connection> {
 
 fn main() -> Result<()> {
     smol::run(async {
-        let addr = ([127, 0, 0, 1], 3000).into();
-        let service =
-            make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(shortner_service)) });
+        let config = Arc::new(Config::from_env());
+        let addr = config
+            .listen_addr()
+            .parse()
+            .with_context(|| format!("Invalid listen address: {}", config.listen_addr()))?;
+        let db: Db = Arc::new(Mutex::new(init_db(&config.db_path)?));
+
+        // Periodically reap rows whose TTL has elapsed so the table doesn't
+        // grow unbounded. Links created without an expiry are left untouched.
+        let reaper_db = db.clone();
+        smol::Task::spawn(async move {
+            loop {
+                smol::Timer::after(std::time::Duration::from_secs(60)).await;
+                if let Ok(conn) = reaper_db.lock() {
+                    let _ = conn.execute(
+                        "delete from urls where expires_at is not null and expires_at <= ?1",
+                        params![now_secs()],
+                    );
+                }
+            }
+        })
+        .detach();
+
+        let service = make_service_fn(move |_| {
+            let db = db.clone();
+            let config = config.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    shortner_service(req, db.clone(), config.clone())
+                }))
+            }
+        });
         let server = Server::bind(&addr).serve(service);
         println!("Listening on http://{}", addr);
         server.await.unwrap();